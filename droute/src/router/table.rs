@@ -23,8 +23,10 @@ use self::{
 };
 use super::upstreams::Upstreams;
 use crate::Label;
+use arc_swap::ArcSwap;
 use hashbrown::{HashMap, HashSet};
 use log::*;
+use std::sync::Arc;
 use thiserror::Error;
 use trust_dns_client::op::Message;
 
@@ -143,6 +145,72 @@ impl Table {
     }
 }
 
+/// A [`Table`] held behind an [`ArcSwap`] so that it can be rebuilt and
+/// atomically replaced at runtime. `route` takes an owned `Arc<Table>` snapshot
+/// for the duration of a query, so in-flight queries keep using the table they
+/// started with while subsequent queries observe the reloaded one.
+pub struct SharedTable {
+    inner: ArcSwap<Table>,
+}
+
+impl SharedTable {
+    /// Wrap an initial `table`.
+    pub fn new(table: Table) -> Self {
+        Self {
+            inner: ArcSwap::from_pointee(table),
+        }
+    }
+
+    /// Route a query against the table that is live at the moment the query
+    /// arrives.
+    pub(super) async fn route(&self, query: Message, upstreams: &Upstreams) -> Result<Message> {
+        // Take an owned `Arc<Table>` rather than holding the `ArcSwap` fast-path
+        // guard across the query's `.await`, which would stall a concurrent
+        // `reload` trying to swap the pointer.
+        self.inner.load_full().route(query, upstreams).await
+    }
+
+    /// Rebuild a fresh table from `parsed_rules` and, if it validates, swap it
+    /// in atomically. A failed rebuild is logged and the previous table is left
+    /// live rather than taking the resolver down.
+    pub async fn reload(&self, parsed_rules: Vec<ParsedRule>) {
+        match Table::with_parsed(parsed_rules).await {
+            Ok(table) => {
+                self.inner.store(Arc::new(table));
+                info!("Routing table reloaded");
+            }
+            Err(e) => error!("Failed to reload the routing table, keeping the previous one: {}", e),
+        }
+    }
+}
+
+/// Spawn a background task that reloads `table` every time a `SIGHUP` is
+/// received, drawing a fresh set of `ParsedRule`s from `reparse`. Re-reading
+/// the configuration and domain lists is left to the caller so that the same
+/// machinery serves both `SIGHUP` and `notify`-driven file watches.
+#[cfg(unix)]
+pub fn spawn_sighup_reload<F, Fut>(table: Arc<SharedTable>, reparse: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Vec<ParsedRule>> + Send,
+{
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Failed to install the SIGHUP handler, hot-reload disabled: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading the routing table");
+            table.reload(reparse().await).await;
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
@@ -14,14 +14,16 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 mod any;
+mod cidr;
 mod domain;
+mod expr;
 #[cfg(feature = "geoip")]
 mod geoip;
 mod qtype;
 
 #[cfg(feature = "geoip")]
 pub use self::geoip::Geoip;
-pub use self::{any::Any, domain::Domain, qtype::QType};
+pub use self::{any::Any, cidr::Cidr, domain::Domain, expr::Expr, qtype::QType};
 
 #[cfg(feature = "geoip")]
 use maxminddb::MaxMindDBError;
@@ -46,6 +48,10 @@ pub enum MatchError {
     /// Malformatted file provided to a matcher.
     #[error("File provided for matcher(s) is malformatted.")]
     Malformatted,
+
+    /// A boolean expression provided to the `expr` matcher could not be parsed.
+    #[error("Failed to parse the boolean expression provided to the `expr` matcher: {0}")]
+    BadExpression(String),
 }
 
 /// A matcher determines if something matches or not given the queries and responses.
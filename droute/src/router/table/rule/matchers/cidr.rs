@@ -0,0 +1,121 @@
+// Copyright 2020 LEXUGE
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::{super::super::parsed::ParsedMatcher, MatchError, Matcher, Result};
+use ipnet::IpNet;
+use std::str::FromStr;
+use tokio::{fs::File, prelude::*};
+use trust_dns_proto::{
+    op::query::Query,
+    rr::{record_data::RData, resource::Record},
+};
+
+/// A matcher that matches on the IP addresses carried in the response
+/// `A`/`AAAA` records, useful for re-routing or dropping answers that resolve
+/// into a given (e.g. internal or anycast) block where GeoIP cannot help.
+pub struct Cidr(Vec<IpNet>);
+
+impl Cidr {
+    // Built from the `ParsedMatcher::Cidr { inline, files }` config variant by
+    // the matcher dispatch in the `rule` builder (both defined outside this
+    // source snapshot, alongside the other `ParsedMatcher` variants).
+    pub async fn new(spec: ParsedMatcher) -> Result<Self> {
+        Ok(match spec {
+            ParsedMatcher::Cidr { inline, files } => {
+                let mut nets = Vec::new();
+                for n in inline {
+                    nets.push(parse(&n)?);
+                }
+                for f in files {
+                    let mut file = File::open(f).await?;
+                    let mut data = String::new();
+                    file.read_to_string(&mut data).await?;
+                    for line in data.lines() {
+                        let line = line.trim();
+                        if line.is_empty() || line.starts_with('#') {
+                            continue;
+                        }
+                        nets.push(parse(line)?);
+                    }
+                }
+                Self(nets)
+            }
+            _ => unreachable!(),
+        })
+    }
+}
+
+// Accept both a bare address (treated as a /32 or /128) and a prefix.
+fn parse(s: &str) -> Result<IpNet> {
+    IpNet::from_str(s)
+        .or_else(|_| s.parse::<std::net::IpAddr>().map(IpNet::from))
+        .map_err(|_| MatchError::Malformatted)
+}
+
+impl Matcher for Cidr {
+    fn matches(&self, _: &[Query], resps: &[Record]) -> bool {
+        resps.iter().any(|r| {
+            let addr = match r.rdata() {
+                RData::A(ip) => std::net::IpAddr::V4(*ip),
+                RData::AAAA(ip) => std::net::IpAddr::V6(*ip),
+                _ => return false,
+            };
+            self.0.iter().any(|net| net.contains(&addr))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Cidr, Matcher};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use trust_dns_proto::rr::{record_data::RData, resource::Record, Name};
+
+    fn cidr(specs: &[&str]) -> Cidr {
+        Cidr(specs.iter().map(|s| parse(s).unwrap()).collect())
+    }
+
+    fn resp(rdata: RData) -> Vec<Record> {
+        vec![Record::from_rdata(Name::root(), 0, rdata)]
+    }
+
+    #[test]
+    fn matches_v4_prefix() {
+        let c = cidr(&["10.0.0.0/8"]);
+        assert!(c.matches(&[], &resp(RData::A(Ipv4Addr::new(10, 1, 2, 3)))));
+        assert!(!c.matches(&[], &resp(RData::A(Ipv4Addr::new(11, 0, 0, 1)))));
+    }
+
+    #[test]
+    fn matches_v6_prefix() {
+        let c = cidr(&["fd00::/8"]);
+        assert!(c.matches(&[], &resp(RData::AAAA(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)))));
+        assert!(!c.matches(&[], &resp(RData::AAAA(Ipv6Addr::new(0xfe00, 0, 0, 0, 0, 0, 0, 1)))));
+    }
+
+    #[test]
+    fn matches_bare_address() {
+        let c = cidr(&["1.2.3.4"]);
+        assert!(c.matches(&[], &resp(RData::A(Ipv4Addr::new(1, 2, 3, 4)))));
+        assert!(!c.matches(&[], &resp(RData::A(Ipv4Addr::new(1, 2, 3, 5)))));
+    }
+
+    #[test]
+    fn ignores_non_address_records() {
+        let c = cidr(&["10.0.0.0/8"]);
+        assert!(!c.matches(&[], &[]));
+        assert!(!c.matches(&[], &resp(RData::CNAME(Name::root()))));
+    }
+}
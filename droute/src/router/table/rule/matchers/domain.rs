@@ -13,23 +13,81 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{super::super::parsed::ParsedMatcher, Matcher, Result};
+use super::{super::super::parsed::ParsedMatcher, MatchError, Matcher, Result};
 use dmatcher::domain::Domain as DomainAlg;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tokio::{fs::File, prelude::*};
 use trust_dns_proto::{op::query::Query, rr::resource::Record};
 
-pub struct Domain(DomainAlg);
+/// Where a domain list is sourced from. A `File` is read once at startup; a
+/// `Remote` list is fetched over HTTPS, refreshed on a TTL and cached to disk
+/// so that startup can still succeed offline.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainSource {
+    /// A local file listing one domain per line.
+    File(PathBuf),
+    /// A list fetched over HTTPS.
+    Remote {
+        /// The URL to fetch the list from.
+        url: String,
+        /// How often to re-download the list.
+        refresh_interval: Duration,
+        /// Where the last successful download is cached for offline startup.
+        cache_path: PathBuf,
+    },
+}
+
+pub struct Domain(Arc<RwLock<DomainAlg>>);
 
 impl Domain {
     pub async fn new(spec: ParsedMatcher) -> Result<Self> {
         Ok(match spec {
-            ParsedMatcher::Domain(p) => {
-                let mut matcher = DomainAlg::new();
-                for r in p {
-                    let mut file = File::open(r).await?;
-                    let mut data = String::new();
-                    file.read_to_string(&mut data).await?;
-                    matcher.insert_multi(&data);
+            ParsedMatcher::Domain(sources) => {
+                let matcher = Arc::new(RwLock::new(DomainAlg::new()));
+                for source in sources {
+                    match source {
+                        DomainSource::File(path) => {
+                            let data = read_file(&path).await?;
+                            matcher.write().unwrap().insert_multi(&data);
+                        }
+                        DomainSource::Remote {
+                            url,
+                            refresh_interval,
+                            cache_path,
+                        } => {
+                            // Prime the matcher, preferring a fresh download but
+                            // falling back to the on-disk cache when offline.
+                            let data = match fetch(&url).await {
+                                Ok(data) => {
+                                    cache(&cache_path, &data).await;
+                                    data
+                                }
+                                Err(e) => {
+                                    warn!(
+                                        "Failed to fetch `{}`, falling back to cache `{}`: {}",
+                                        url,
+                                        cache_path.display(),
+                                        e
+                                    );
+                                    read_file(&cache_path).await?
+                                }
+                            };
+                            matcher.write().unwrap().insert_multi(&data);
+                            spawn_refresh(
+                                Arc::clone(&matcher),
+                                url,
+                                refresh_interval,
+                                cache_path,
+                            );
+                        }
+                    }
                 }
                 Self(matcher)
             }
@@ -40,6 +98,92 @@ impl Domain {
 
 impl Matcher for Domain {
     fn matches(&self, queries: &[Query], _: &[Record]) -> bool {
-        self.0.matches(&queries[0].name().to_utf8())
+        // Recover from a poisoned lock rather than panicking on every query: if
+        // the refresh task panicked mid-swap the trie is still readable, and a
+        // stale match is preferable to taking the resolver down.
+        let matcher = self.0.read().unwrap_or_else(|e| e.into_inner());
+        matcher.matches(&queries[0].name().to_utf8())
+    }
+}
+
+async fn read_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut data = String::new();
+    file.read_to_string(&mut data).await?;
+    Ok(data)
+}
+
+async fn cache(path: &Path, data: &str) {
+    if let Err(e) = tokio::fs::write(path, data).await {
+        warn!("Failed to cache domain list to `{}`: {}", path.display(), e);
     }
 }
+
+// Download a domain list over HTTPS reusing the crate's rustls configuration.
+async fn fetch(url: &str) -> Result<String> {
+    use crate::router::upstreams::client_pool::crypto::{create_client_config, TlsOpts};
+    use hyper::{body, Client, Uri};
+    use hyper_rustls::HttpsConnector;
+
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| MatchError::IOError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid remote domain list URL",
+        )))?;
+    let client_config = create_client_config(&TlsOpts::default())
+        .map_err(|e| MatchError::IOError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    let connector = HttpsConnector::from((
+        {
+            let mut http = hyper::client::HttpConnector::new();
+            http.enforce_http(false);
+            http
+        },
+        client_config,
+    ));
+    let client = Client::builder().build::<_, hyper::Body>(connector);
+    let resp = client
+        .get(uri)
+        .await
+        .map_err(|e| MatchError::IOError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    // Guard against silently ingesting a 404/500 error page as if it were a
+    // valid list; a non-2xx response falls back to the cache upstream.
+    if !resp.status().is_success() {
+        return Err(MatchError::IOError(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("remote domain list returned HTTP status {}", resp.status()),
+        )));
+    }
+    let bytes = body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| MatchError::IOError(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    String::from_utf8(bytes.to_vec())
+        .map_err(|e| MatchError::IOError(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+}
+
+// Re-download the list every `refresh_interval`, rebuilding a fresh trie from
+// scratch and swapping it in under the write lock on success so that domains
+// dropped upstream stop matching, and leaving the live matcher untouched on
+// failure.
+fn spawn_refresh(
+    matcher: Arc<RwLock<DomainAlg>>,
+    url: String,
+    refresh_interval: Duration,
+    cache_path: PathBuf,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::delay_for(refresh_interval).await;
+            match fetch(&url).await {
+                Ok(data) => {
+                    let mut fresh = DomainAlg::new();
+                    fresh.insert_multi(&data);
+                    *matcher.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+                    cache(&cache_path, &data).await;
+                    info!("Refreshed remote domain list `{}`", url);
+                }
+                Err(e) => warn!("Failed to refresh remote domain list `{}`: {}", url, e),
+            }
+        }
+    });
+}
@@ -0,0 +1,367 @@
+// Copyright 2020 LEXUGE
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::{MatchError, Matcher, Result};
+use crate::Label;
+use hashbrown::HashMap;
+use trust_dns_proto::{op::query::Query, rr::resource::Record};
+
+// Tokens produced by the lexer. Leaf atoms (an identifier, optionally
+// followed by a parenthesised literal such as `domain("china")`) are kept
+// whole so that the grouping parentheses below are never ambiguous with a
+// matcher call.
+enum Token {
+    Leaf(Label),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+// Operators in reverse-polish order. Leaves carry the label used to look up
+// the already-constructed matcher.
+enum Rpn {
+    Leaf(Label),
+    And,
+    Or,
+    Not,
+}
+
+/// A matcher that evaluates a boolean expression such as
+/// `domain("china") && !qtype(AAAA) || geoip("CN")` over a set of named
+/// matchers. The precedence is `!` > `&&` > `||`.
+pub struct Expr {
+    rpn: Vec<Rpn>,
+    matchers: HashMap<Label, Box<dyn Matcher>>,
+}
+
+impl Expr {
+    /// Parse `expr` into a boolean expression tree over `matchers`, each leaf
+    /// naming one of the already-constructed matchers. Parse failures and
+    /// references to undefined matchers are reported as
+    /// [`MatchError::BadExpression`].
+    ///
+    /// The `ParsedMatcher::Expr` config variant and the build site that
+    /// assembles the named-matcher map before calling this live in
+    /// `table/parsed.rs` and the `rule` builder, neither of which is part of
+    /// this source snapshot.
+    pub fn new(expr: &str, matchers: HashMap<Label, Box<dyn Matcher>>) -> Result<Self> {
+        let rpn = Self::parse(&Self::tokenize(expr)?)?;
+        Self::validate(&rpn, &matchers)?;
+        Ok(Self { rpn, matchers })
+    }
+
+    // Reject expressions whose operators lack the operands they pop at
+    // evaluation time (`qtype(A) &&`, a lone `!`, two adjacent leaves, ...).
+    // Simulating the stack depth here turns what would otherwise be a
+    // per-query panic into a build-time [`MatchError::BadExpression`], and also
+    // verifies every leaf names a matcher that actually exists.
+    fn validate(rpn: &[Rpn], matchers: &HashMap<Label, Box<dyn Matcher>>) -> Result<()> {
+        let mut depth: usize = 0;
+        for t in rpn {
+            match t {
+                Rpn::Leaf(l) => {
+                    if !matchers.contains_key(l) {
+                        return Err(MatchError::BadExpression(format!(
+                            "undefined matcher `{}`",
+                            l
+                        )));
+                    }
+                    depth += 1;
+                }
+                Rpn::Not => {
+                    if depth < 1 {
+                        return Err(MatchError::BadExpression(
+                            "`!` is missing its operand".into(),
+                        ));
+                    }
+                }
+                Rpn::And | Rpn::Or => {
+                    if depth < 2 {
+                        return Err(MatchError::BadExpression(
+                            "a binary operator is missing an operand".into(),
+                        ));
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+        if depth != 1 {
+            return Err(MatchError::BadExpression(
+                "the expression does not reduce to a single value".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn tokenize(expr: &str) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut chars = expr.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            match c {
+                c if c.is_whitespace() => {
+                    chars.next();
+                }
+                '(' => {
+                    chars.next();
+                    tokens.push(Token::LParen);
+                }
+                ')' => {
+                    chars.next();
+                    tokens.push(Token::RParen);
+                }
+                '!' => {
+                    chars.next();
+                    tokens.push(Token::Not);
+                }
+                '&' => {
+                    chars.next();
+                    if chars.next() != Some('&') {
+                        return Err(MatchError::BadExpression("expected `&&`".into()));
+                    }
+                    tokens.push(Token::And);
+                }
+                '|' => {
+                    chars.next();
+                    if chars.next() != Some('|') {
+                        return Err(MatchError::BadExpression("expected `||`".into()));
+                    }
+                    tokens.push(Token::Or);
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    // An atom: identifier plus an optional parenthesised
+                    // string/enum literal, kept verbatim as the leaf label.
+                    let mut leaf = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_alphanumeric() || c == '_' {
+                            leaf.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    if chars.peek() == Some(&'(') {
+                        leaf.push('(');
+                        chars.next();
+                        while let Some(&c) = chars.peek() {
+                            leaf.push(c);
+                            chars.next();
+                            if c == ')' {
+                                break;
+                            }
+                        }
+                        if !leaf.ends_with(')') {
+                            return Err(MatchError::BadExpression(
+                                "unterminated matcher call".into(),
+                            ));
+                        }
+                    }
+                    tokens.push(Token::Leaf(leaf.into()));
+                }
+                c => {
+                    return Err(MatchError::BadExpression(format!(
+                        "unexpected character `{}`",
+                        c
+                    )))
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    // Shunting-yard: turn the infix token stream into RPN. `!` binds tighter
+    // than `&&`, which binds tighter than `||`; `!` is right-associative.
+    fn parse(tokens: &[Token]) -> Result<Vec<Rpn>> {
+        fn prec(op: &Token) -> u8 {
+            match op {
+                Token::Not => 3,
+                Token::And => 2,
+                Token::Or => 1,
+                _ => 0,
+            }
+        }
+
+        let mut output = Vec::new();
+        let mut ops: Vec<&Token> = Vec::new();
+        for token in tokens {
+            match token {
+                Token::Leaf(l) => output.push(Rpn::Leaf(l.clone())),
+                Token::Not => ops.push(token),
+                Token::And | Token::Or => {
+                    while let Some(top) = ops.last() {
+                        if matches!(top, Token::LParen) || prec(top) < prec(token) {
+                            break;
+                        }
+                        Self::pop_op(&mut output, ops.pop().unwrap())?;
+                    }
+                    ops.push(token);
+                }
+                Token::LParen => ops.push(token),
+                Token::RParen => {
+                    loop {
+                        match ops.pop() {
+                            Some(Token::LParen) => break,
+                            Some(op) => Self::pop_op(&mut output, op)?,
+                            None => {
+                                return Err(MatchError::BadExpression(
+                                    "unbalanced parentheses".into(),
+                                ))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        while let Some(op) = ops.pop() {
+            if matches!(op, Token::LParen) {
+                return Err(MatchError::BadExpression("unbalanced parentheses".into()));
+            }
+            Self::pop_op(&mut output, op)?;
+        }
+        Ok(output)
+    }
+
+    fn pop_op(output: &mut Vec<Rpn>, op: &Token) -> Result<()> {
+        match op {
+            Token::Not => output.push(Rpn::Not),
+            Token::And => output.push(Rpn::And),
+            Token::Or => output.push(Rpn::Or),
+            _ => return Err(MatchError::BadExpression("malformed expression".into())),
+        }
+        Ok(())
+    }
+}
+
+impl Matcher for Expr {
+    fn matches(&self, queries: &[Query], resps: &[Record]) -> bool {
+        let mut stack: Vec<bool> = Vec::new();
+        for token in &self.rpn {
+            match token {
+                // A leaf is validated to exist in `new`, so the lookup and the
+                // operand pops below cannot fail on a well-formed stack.
+                Rpn::Leaf(l) => stack.push(self.matchers[l].matches(queries, resps)),
+                Rpn::Not => {
+                    let v = stack.pop().unwrap();
+                    stack.push(!v);
+                }
+                Rpn::And => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(a && b);
+                }
+                Rpn::Or => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(a || b);
+                }
+            }
+        }
+        stack.pop().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, MatchError, Matcher};
+    use crate::Label;
+    use hashbrown::HashMap;
+    use trust_dns_proto::{op::query::Query, rr::resource::Record};
+
+    // A leaf matcher with a fixed verdict, so the tests exercise the parser and
+    // evaluator without depending on any real query or record.
+    struct Fixed(bool);
+
+    impl Matcher for Fixed {
+        fn matches(&self, _: &[Query], _: &[Record]) -> bool {
+            self.0
+        }
+    }
+
+    fn matchers(pairs: &[(&str, bool)]) -> HashMap<Label, Box<dyn Matcher>> {
+        pairs
+            .iter()
+            .map(|(k, v)| (Label::from(*k), Box::new(Fixed(*v)) as Box<dyn Matcher>))
+            .collect()
+    }
+
+    fn eval(expr: &str, pairs: &[(&str, bool)]) -> bool {
+        Expr::new(expr, matchers(pairs)).unwrap().matches(&[], &[])
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // Parsed as `(a && b) || c`, so a false `a` still yields `c`.
+        assert!(eval("a && b || c", &[("a", false), ("b", false), ("c", true)]));
+        assert!(!eval(
+            "a && b || c",
+            &[("a", true), ("b", false), ("c", false)]
+        ));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        // Parsed as `(!a) && b`, not `!(a && b)`.
+        assert!(!eval("!a && b", &[("a", true), ("b", false)]));
+        assert!(eval("!a && b", &[("a", false), ("b", true)]));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert!(eval(
+            "a && (b || c)",
+            &[("a", true), ("b", false), ("c", true)]
+        ));
+        // Without the parentheses this would be `(a && b) || c`, i.e. true.
+        assert!(!eval(
+            "a && (b || c)",
+            &[("a", false), ("b", false), ("c", true)]
+        ));
+    }
+
+    #[test]
+    fn unbalanced_parentheses_rejected() {
+        assert!(matches!(
+            Expr::new("a && (b", matchers(&[("a", true), ("b", true)])),
+            Err(MatchError::BadExpression(_))
+        ));
+        assert!(matches!(
+            Expr::new("a) && b", matchers(&[("a", true), ("b", true)])),
+            Err(MatchError::BadExpression(_))
+        ));
+    }
+
+    #[test]
+    fn undefined_matcher_rejected() {
+        assert!(matches!(
+            Expr::new("a && missing", matchers(&[("a", true)])),
+            Err(MatchError::BadExpression(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_arity_rejected() {
+        for expr in &["a &&", "!", "a b", "&& a", "a || || b"] {
+            assert!(
+                matches!(
+                    Expr::new(expr, matchers(&[("a", true), ("b", true)])),
+                    Err(MatchError::BadExpression(_))
+                ),
+                "`{}` should have been rejected",
+                expr
+            );
+        }
+    }
+}
@@ -25,23 +25,168 @@ pub use self::https::Https;
 #[cfg(feature = "dot")]
 pub use self::tls::Tls;
 
-use rustls::{ClientConfig, KeyLogFile, ProtocolVersion, RootCertStore};
-use std::sync::Arc;
+use rustls::{
+    Certificate, ClientConfig, KeyLogFile, ProtocolVersion, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, TLSError,
+};
+use std::{
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use webpki::DNSNameRef;
 
 const ALPN_H2: &[u8] = b"h2";
 
-// Create client config for TLS and HTTPS clients
-fn create_client_config(no_sni: &bool) -> Arc<ClientConfig> {
+/// Errors generated while building a TLS client configuration.
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    /// A certificate or key file could not be read.
+    #[error("Failed to read a certificate or key file: {0}")]
+    IOError(#[from] std::io::Error),
+
+    /// A PEM certificate file could not be parsed.
+    #[error("Failed to parse the PEM certificate file `{0}`")]
+    BadCert(String),
+
+    /// A pinned fingerprint was not valid hexadecimal.
+    #[error("The configured certificate fingerprint is not valid hexadecimal")]
+    BadFingerprint,
+
+    /// A client private key could not be parsed as PKCS#8 or RSA.
+    #[error("Failed to parse the client private key `{0}` as PKCS#8 or RSA")]
+    BadKey(String),
+
+    /// The client certificate and key were rejected by rustls (e.g. mismatch).
+    #[error("Failed to set the client certificate for mutual TLS: {0}")]
+    ClientAuth(String),
+}
+
+/// Per-upstream TLS options. The defaults reproduce the historical behaviour:
+/// the `webpki_roots` trust anchors, TLS 1.2 only, and a normal CA-backed
+/// verifier.
+#[derive(Clone, Default)]
+pub(crate) struct TlsOpts {
+    /// Disable SNI.
+    pub no_sni: bool,
+    /// An extra PEM bundle whose certificates are added to the root store, for
+    /// private or corporate CAs.
+    pub extra_ca: Option<PathBuf>,
+    /// Trust TLS 1.3 in addition to TLS 1.2.
+    pub tls13: bool,
+    /// Pin the upstream leaf certificate to this SHA-256 fingerprint (hex).
+    pub pin: Option<String>,
+    /// A PEM client certificate chain to present for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    /// The PKCS#8 or RSA private key matching `client_cert`.
+    pub client_key: Option<PathBuf>,
+}
+
+// Verifier that accepts a connection only if the presented leaf certificate
+// hashes to the configured SHA-256 fingerprint, independent of any CA chain.
+struct PinnedCertVerifier {
+    fingerprint: Vec<u8>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let leaf = presented_certs
+            .first()
+            .ok_or_else(|| TLSError::General("no certificate presented by the server".into()))?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, &leaf.0);
+        if digest.as_ref() == self.fingerprint.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "presented certificate does not match the pinned fingerprint".into(),
+            ))
+        }
+    }
+}
+
+fn parse_fingerprint(hex: &str) -> Result<Vec<u8>, CryptoError> {
+    let hex: String = hex.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 {
+        return Err(CryptoError::BadFingerprint);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| CryptoError::BadFingerprint))
+        .collect()
+}
+
+// Build the rustls client config from the given `TlsOpts`, applying the extra
+// root CAs, TLS 1.3, certificate pinning and client certificate they request.
+pub(crate) fn create_client_config(opts: &TlsOpts) -> Result<Arc<ClientConfig>, CryptoError> {
     let mut root_store = RootCertStore::empty();
     root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-    let versions = vec![ProtocolVersion::TLSv1_2];
+    if let Some(path) = &opts.extra_ca {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        root_store
+            .add_pem_file(&mut reader)
+            .map_err(|_| CryptoError::BadCert(path.display().to_string()))?;
+    }
+
+    let mut versions = vec![ProtocolVersion::TLSv1_2];
+    if opts.tls13 {
+        versions.push(ProtocolVersion::TLSv1_3);
+    }
 
     let mut client_config = ClientConfig::new();
     client_config.root_store = root_store;
     client_config.versions = versions;
     client_config.alpn_protocols.push(ALPN_H2.to_vec());
     client_config.key_log = Arc::new(KeyLogFile::new());
-    client_config.enable_sni = !no_sni; // Disable SNI on need.
+    client_config.enable_sni = !opts.no_sni; // Disable SNI on need.
+
+    if let Some(pin) = &opts.pin {
+        let fingerprint = parse_fingerprint(pin)?;
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(PinnedCertVerifier { fingerprint }));
+    }
+
+    if let (Some(cert_path), Some(key_path)) = (&opts.client_cert, &opts.client_key) {
+        let certs = load_certs(cert_path)?;
+        let key = load_private_key(key_path)?;
+        client_config
+            .set_single_client_cert(certs, key)
+            .map_err(|e| CryptoError::ClientAuth(e.to_string()))?;
+    }
+
+    Ok(Arc::new(client_config))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, CryptoError> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    rustls::internal::pemfile::certs(&mut reader)
+        .map_err(|_| CryptoError::BadCert(path.display().to_string()))
+}
+
+// Accept either a PKCS#8 or a legacy RSA private key, matching what rustls can
+// parse from a PEM file.
+fn load_private_key(path: &Path) -> Result<rustls::PrivateKey, CryptoError> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if let Ok(mut keys) = rustls::internal::pemfile::pkcs8_private_keys(&mut reader) {
+        if let Some(key) = keys.pop() {
+            return Ok(key);
+        }
+    }
+
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    if let Ok(mut keys) = rustls::internal::pemfile::rsa_private_keys(&mut reader) {
+        if let Some(key) = keys.pop() {
+            return Ok(key);
+        }
+    }
 
-    Arc::new(client_config)
+    Err(CryptoError::BadKey(path.display().to_string()))
 }